@@ -25,10 +25,12 @@
 //! build::transaction(vec![input_rand(75), output_rand(42), output_rand(32),
 //!   with_fee(1)])
 
-use byteorder::{ByteOrder, BigEndian};
+use blake2_rfc::blake2b::blake2b;
+use rand::OsRng;
 use secp;
+use secp::key::{PublicKey, SecretKey};
 
-use core::{Transaction, Input, Output, DEFAULT_OUTPUT};
+use core::{Transaction, Input, Output, Script, DEFAULT_OUTPUT, signing_digest};
 use keychain;
 use keychain::{Keychain, BlindSum, BlindingFactor, Identifier};
 
@@ -50,22 +52,77 @@ pub fn input(value: u64, pubkey: Identifier) -> Box<Append> {
 	})
 }
 
+/// Builds the commitment, rewindable range proof and `Output` shared by
+/// `output` and `output_with_script`, so a future fix to the rewind/commit
+/// logic only has to be made once. `script`, if any, is attached to the
+/// output but its script-offset contribution is the caller's job.
+fn build_output(
+	build: &mut Context,
+	value: u64,
+	pubkey: &Identifier,
+	script: Option<Script>,
+) -> (secp::pedersen::Commitment, Output) {
+	let commit = build.keychain.commit(value, pubkey).unwrap();
+	let msg = rewind_message(pubkey);
+	let nonce = build.keychain.rewind_nonce(&commit).unwrap();
+	let rproof = build.keychain.range_proof(value, pubkey, commit, msg, nonce).unwrap();
+
+	(commit, Output {
+		features: DEFAULT_OUTPUT,
+		commit: commit,
+		proof: rproof,
+		script: script,
+	})
+}
+
 /// Adds an output with the provided value and blinding key to the transaction
-/// being built.
+/// being built. The range proof embeds a message encoding this output's
+/// derivation path, rewindable with the keychain alone, so a wallet that
+/// lost its state can later rescan the chain and recover it with
+/// `rewind_output`.
 pub fn output(value: u64, pubkey: Identifier) -> Box<Append> {
 	Box::new(move |build, (tx, sum)| -> (Transaction, BlindSum) {
-		let commit = build.keychain.commit(value, &pubkey).unwrap();
-		let msg = secp::pedersen::ProofMessage::empty();
-		let rproof = build.keychain.range_proof(value, &pubkey, commit, msg).unwrap();
-
-		(tx.with_output(Output {
-			features: DEFAULT_OUTPUT,
-			commit: commit,
-			proof: rproof,
-		}), sum.add_pubkey(pubkey.clone()))
+		let (_, out) = build_output(build, value, &pubkey, None);
+		(tx.with_output(out), sum.add_pubkey(pubkey.clone()))
 	})
 }
 
+/// Hashes a script to the scalar used as its script-offset contribution.
+fn script_hash(script: &Script, secp: &secp::Secp256k1) -> Result<SecretKey, keychain::Error> {
+	let hash = blake2b(32, &[], script.bytes());
+	SecretKey::from_slice(secp, hash.as_bytes()).map_err(keychain::Error::Secp)
+}
+
+/// Adds an output carrying a spending condition. Everything `output` does,
+/// plus deriving this output's contribution `γ_i = hash(script_i)·key_i`
+/// to the running script offset `Σγ`, which gets folded into the blinding
+/// sum the same way the kernel offset is: the resulting signature only
+/// verifies against the exact set of scripts that went into building it.
+pub fn output_with_script(value: u64, pubkey: Identifier, script: Script) -> Box<Append> {
+	Box::new(move |build, (tx, sum)| -> (Transaction, BlindSum) {
+		let gamma = script_offset_contribution(&script, &pubkey, build.keychain).unwrap();
+		let (_, out) = build_output(build, value, &pubkey, Some(script.clone()));
+
+		let total_script_offset = tx.script_offset
+			.add(build.keychain.secp(), &BlindingFactor::from_secret_key(gamma.clone()))
+			.unwrap();
+
+		(tx.with_output(out).with_script_offset(total_script_offset),
+		sum.add_pubkey(pubkey.clone()).sub_script_offset(gamma))
+	})
+}
+
+/// This output's contribution to the running script offset, `hash(script)
+/// · key`, where `key` is the secret key behind its derivation path.
+fn script_offset_contribution(
+	script: &Script,
+	pubkey: &Identifier,
+	keychain: &Keychain,
+) -> Result<SecretKey, keychain::Error> {
+	let hash = script_hash(script, keychain.secp())?;
+	keychain.scale_by_key(pubkey, &hash)
+}
+
 /// Sets the fee on the transaction being built.
 pub fn with_fee(fee: u64) -> Box<Append> {
 	Box::new(move |_build, (tx, sum)| -> (Transaction, BlindSum) {
@@ -89,6 +146,19 @@ pub fn initial_tx(tx: Transaction) -> Box<Append> {
 	})
 }
 
+/// Sets (or adds to) the kernel offset on the transaction being built. The
+/// offset is subtracted from the blinding sum before the excess signature is
+/// produced, so the kernel only commits to `blind_sum - offset` instead of
+/// the raw sum. Chaining this with `initial_tx`/`with_excess` accumulates
+/// the offset, so the total offset of the resulting transaction is always
+/// the sum of the individual offsets that went into it.
+pub fn with_offset(offset: BlindingFactor) -> Box<Append> {
+	Box::new(move |build, (tx, sum)| -> (Transaction, BlindSum) {
+		let total = tx.offset.add(build.keychain.secp(), &offset).unwrap();
+		(tx.with_offset(total), sum.sub_offset(offset.clone()))
+	})
+}
+
 /// Builds a new transaction by combining all the combinators provided in a
 /// Vector. Transactions can either be built "from scratch" with a list of
 /// inputs or outputs or from a pre-existing transaction that gets added to.
@@ -104,20 +174,337 @@ pub fn transaction(
 	keychain: &keychain::Keychain,
 ) -> Result<(Transaction, BlindingFactor), keychain::Error> {
 	let mut ctx = Context { keychain };
-	let (mut tx, sum) = elems.iter().fold(
+	let (mut tx, mut sum) = elems.iter().fold(
 		(Transaction::empty(), BlindSum::new()), |acc, elem| elem(&mut ctx, acc)
 	);
+
+	// Every transaction needs a kernel offset to keep its excess from
+	// leaking once aggregated with others. If none of the combinators set
+	// one explicitly, generate a fresh random one here.
+	if tx.offset == BlindingFactor::zero() {
+		let offset = random_offset(ctx.keychain.secp());
+		tx = tx.with_offset(offset.clone());
+		sum = sum.sub_offset(offset);
+	}
+
 	let blind_sum = ctx.keychain.blind_sum(&sum)?;
-	let msg = secp::Message::from_slice(&u64_to_32bytes(tx.fee))?;
+	let msg = secp::Message::from_slice(&signing_digest(tx.fee, tx.lock_height))?;
 	let sig = ctx.keychain.sign_with_blinding(&msg, &blind_sum)?;
 	tx.excess_sig = sig.serialize_der(&ctx.keychain.secp());
 	Ok((tx, blind_sum))
 }
 
-fn u64_to_32bytes(n: u64) -> [u8; 32] {
-	let mut bytes = [0; 32];
-	BigEndian::write_u64(&mut bytes[24..32], n);
-	bytes
+/// Generates a fresh random kernel offset, used to blind the aggregated
+/// excess so individual kernels can no longer be linked once transactions
+/// are merged or cut-through.
+fn random_offset(secp: &secp::Secp256k1) -> BlindingFactor {
+	let mut rng = OsRng::new().expect("failed to get a secure RNG for the kernel offset");
+	BlindingFactor::from_secret_key(SecretKey::new(secp, &mut rng))
+}
+
+/// The data a party to an interactive transaction hands to the others: its
+/// share of the inputs/outputs built so far, its public excess `P_i = x_i·G`
+/// and its public nonce `R_i = r_i·G`. None of this reveals the private
+/// blinding factors or nonce behind it.
+pub struct PartialTx {
+	pub fee: u64,
+	pub lock_height: u64,
+	pub tx: Transaction,
+	pub public_blind_excess: PublicKey,
+	pub public_nonce: PublicKey,
+}
+
+/// Builds this party's share of an interactive transaction from the given
+/// combinators, without signing it. Returns the `PartialTx` to hand to the
+/// other parties, along with this party's private blinding sum and nonce,
+/// which must stay local and are needed later by `sign_partial`.
+pub fn partial_transaction(
+	elems: Vec<Box<Append>>,
+	fee: u64,
+	lock_height: u64,
+	keychain: &keychain::Keychain,
+) -> Result<(PartialTx, BlindingFactor, SecretKey), keychain::Error> {
+	let mut ctx = Context { keychain };
+	let (tx, sum) = elems.iter().fold(
+		(Transaction::empty(), BlindSum::new()), |acc, elem| elem(&mut ctx, acc)
+	);
+
+	// Contribute this party's share of the kernel offset, exactly like
+	// `transaction`'s fallback does, so an interactive transaction doesn't
+	// reintroduce the excess-linkage leak kernel offsets are meant to
+	// close once it's merged/cut-through with others.
+	let secp = ctx.keychain.secp();
+	let party_offset = random_offset(secp);
+	let total_offset = tx.offset.add(secp, &party_offset)?;
+	let tx = tx.with_offset(total_offset).with_fee(fee).with_lock_height(lock_height);
+	let sum = sum.sub_offset(party_offset);
+
+	let blind_sum = ctx.keychain.blind_sum(&sum)?;
+	let nonce = nonce_secret_key(secp);
+
+	let public_blind_excess = PublicKey::from_secret_key(secp, &blind_sum.secret_key(secp)?)?;
+	let public_nonce = PublicKey::from_secret_key(secp, &nonce)?;
+
+	Ok((
+		PartialTx { fee, lock_height, tx, public_blind_excess, public_nonce },
+		blind_sum,
+		nonce,
+	))
+}
+
+/// Computes this party's partial signature `s_i = r_i + e·x_i`, once every
+/// party's `PartialTx` is known. `e` is derived from the aggregate nonce
+/// `R = ΣR_i`, the aggregate excess `P = ΣP_i`, the fee and the lock height,
+/// so every party ends up hashing the exact same challenge.
+pub fn sign_partial(
+	blind_sum: &BlindingFactor,
+	nonce: &SecretKey,
+	parts: &[PartialTx],
+	keychain: &keychain::Keychain,
+) -> Result<SecretKey, keychain::Error> {
+	let secp = keychain.secp();
+	let challenge = aggregate_challenge(parts, secp)?;
+
+	let mut s = blind_sum.secret_key(secp)?;
+	s.mul_assign(secp, &challenge)?;
+	s.add_assign(secp, nonce)?;
+	Ok(s)
+}
+
+/// Verifies a party's partial signature against its public contribution,
+/// checking `s_i·G == R_i + e·P_i` before it's folded into the final sig.
+pub fn verify_partial_sig(
+	partial_sig: &SecretKey,
+	partial: &PartialTx,
+	parts: &[PartialTx],
+	keychain: &keychain::Keychain,
+) -> Result<(), keychain::Error> {
+	let secp = keychain.secp();
+	let challenge = aggregate_challenge(parts, secp)?;
+
+	let mut e_p = partial.public_blind_excess.clone();
+	e_p.mul_assign(secp, &challenge)?;
+	let expected = PublicKey::from_combination(secp, vec![&partial.public_nonce, &e_p])?;
+	let actual = PublicKey::from_secret_key(secp, partial_sig)?;
+
+	if actual == expected {
+		Ok(())
+	} else {
+		Err(keychain::Error::Secp(secp::Error::IncorrectSignature))
+	}
+}
+
+/// Merges every party's partial transaction and partial signature into the
+/// final, fully signed transaction.
+pub fn finalize_transaction(
+	parts: &[PartialTx],
+	partial_sigs: &[SecretKey],
+	keychain: &keychain::Keychain,
+) -> Result<Transaction, keychain::Error> {
+	validate_parts(parts)?;
+	if partial_sigs.len() != parts.len() {
+		return Err(keychain::Error::PartyMismatch);
+	}
+
+	let secp = keychain.secp();
+	let public_nonce_sum = aggregate_keys(secp, parts.iter().map(|p| &p.public_nonce).collect())?;
+
+	let mut s_sum = partial_sigs[0].clone();
+	for s in &partial_sigs[1..] {
+		s_sum.add_assign(secp, s)?;
+	}
+
+	let mut tx = parts[0].tx.clone();
+	for part in &parts[1..] {
+		tx = tx.merge(part.tx.clone(), secp);
+	}
+
+	let sig = secp::Signature::from_rs(secp, &public_nonce_sum, &s_sum)?;
+	tx.excess_sig = sig.serialize_der(secp);
+	Ok(tx)
+}
+
+/// The Schnorr challenge `e`, as a scalar, shared by every party taking
+/// part in an interactive transaction. Built over the same
+/// `signing_digest(fee, lock_height)` that `Transaction::verify_sig` checks
+/// the finalized signature against, so `lock_height` ends up authenticated
+/// on the final transaction instead of only influencing the partial sigs.
+fn aggregate_challenge(parts: &[PartialTx], secp: &secp::Secp256k1) -> Result<SecretKey, keychain::Error> {
+	validate_parts(parts)?;
+
+	let public_nonce_sum = aggregate_keys(secp, parts.iter().map(|p| &p.public_nonce).collect())?;
+	let public_excess_sum = aggregate_keys(secp, parts.iter().map(|p| &p.public_blind_excess).collect())?;
+
+	let mut msg = Vec::new();
+	msg.extend_from_slice(&public_nonce_sum.serialize_vec(secp, true)[..]);
+	msg.extend_from_slice(&public_excess_sum.serialize_vec(secp, true)[..]);
+	msg.extend_from_slice(&signing_digest(parts[0].fee, parts[0].lock_height));
+
+	let hash = blake2b(32, &[], &msg);
+	SecretKey::from_slice(secp, hash.as_bytes()).map_err(keychain::Error::Secp)
+}
+
+fn aggregate_keys(secp: &secp::Secp256k1, keys: Vec<&PublicKey>) -> Result<PublicKey, keychain::Error> {
+	PublicKey::from_combination(secp, keys).map_err(keychain::Error::Secp)
+}
+
+/// Checks that `parts` is non-empty and that every party agrees on the
+/// fee and lock height, so `aggregate_challenge`/`finalize_transaction`
+/// never have to index an empty slice or silently sign over whatever
+/// `parts[0]` happened to use.
+fn validate_parts(parts: &[PartialTx]) -> Result<(), keychain::Error> {
+	let first = parts.first().ok_or(keychain::Error::NoParties)?;
+	if parts.iter().any(|p| p.fee != first.fee || p.lock_height != first.lock_height) {
+		return Err(keychain::Error::PartyMismatch);
+	}
+	Ok(())
+}
+
+fn nonce_secret_key(secp: &secp::Secp256k1) -> SecretKey {
+	let mut rng = OsRng::new().expect("failed to get a secure RNG for the signing nonce");
+	SecretKey::new(secp, &mut rng)
+}
+
+/// An unspent output available to the coin selector. `is_change` lets
+/// callers opt out of spending a wallet's own change via
+/// `select_and_build`'s `do_not_spend_change` flag.
+pub struct OutputData {
+	pub value: u64,
+	pub key_id: Identifier,
+	pub is_change: bool,
+}
+
+/// Approximate weight of an empty transaction, priced in the same unit as
+/// `fee_rate`.
+const BASE_WEIGHT: u64 = 10;
+/// Approximate weight contributed by a single input.
+const INPUT_WEIGHT: u64 = 150;
+/// Approximate weight contributed by a single output, range proof included.
+const OUTPUT_WEIGHT: u64 = 350;
+
+/// Selects spendable outputs to cover `target` plus a fee derived from the
+/// resulting transaction's weight, derives a fresh change output from the
+/// keychain for the remainder, and returns the `input`/`output`/`with_fee`
+/// combinators needed to build it. The caller only has to append its own
+/// `output(...)` combinator for the recipient and hand everything to
+/// `build::transaction`, instead of hand-balancing commitments.
+///
+/// Selection is largest-first, which tends to minimize the number of
+/// inputs (and therefore the fee) at the cost of leaving smaller outputs
+/// unspent for later. Set `do_not_spend_change` to exclude a wallet's own
+/// change outputs from selection. Returns an error if no selection of the
+/// given coins covers `target` plus its own fee.
+pub fn select_and_build(
+	coins: &[OutputData],
+	target: u64,
+	fee_rate: u64,
+	change_derivation: u32,
+	do_not_spend_change: bool,
+	keychain: &keychain::Keychain,
+) -> Result<Vec<Box<Append>>, keychain::Error> {
+	let mut candidates: Vec<&OutputData> = coins.iter()
+		.filter(|c| !do_not_spend_change || !c.is_change)
+		.collect();
+	candidates.sort_by(|a, b| b.value.cmp(&a.value));
+
+	let mut selected = Vec::new();
+	let mut total = 0u64;
+	let mut fee = tx_fee(0, 2, fee_rate);
+	for coin in candidates {
+		selected.push(coin);
+		total += coin.value;
+		fee = tx_fee(selected.len(), 2, fee_rate);
+		if total >= target + fee {
+			break;
+		}
+	}
+
+	if total < target + fee {
+		return Err(keychain::Error::NotEnoughFunds(total));
+	}
+
+	// If the selected inputs cover the target exactly, there's no change to
+	// hand back: drop the change output entirely instead of emitting a
+	// worthless zero-value one. The whole remainder still has to go to the
+	// fee to keep the transaction balanced, which a one-output tx can
+	// easily afford — it's always above the one-output fee estimate.
+	let change = total - target - fee;
+	if change == 0 {
+		debug_assert!(fee >= tx_fee(selected.len(), 1, fee_rate));
+		let mut elems: Vec<Box<Append>> = selected.into_iter()
+			.map(|coin| input(coin.value, coin.key_id.clone()))
+			.collect();
+		elems.push(with_fee(fee));
+		return Ok(elems);
+	}
+
+	let change_key = keychain.derive_pubkey(change_derivation)?;
+	let mut elems: Vec<Box<Append>> = selected.into_iter()
+		.map(|coin| input(coin.value, coin.key_id.clone()))
+		.collect();
+	elems.push(output(change, change_key));
+	elems.push(with_fee(fee));
+	Ok(elems)
+}
+
+/// Transaction weight given a number of inputs and outputs, converted to a
+/// fee at the given rate.
+fn tx_fee(n_inputs: usize, n_outputs: usize, fee_rate: u64) -> u64 {
+	let weight = BASE_WEIGHT + (n_inputs as u64) * INPUT_WEIGHT + (n_outputs as u64) * OUTPUT_WEIGHT;
+	weight * fee_rate
+}
+
+/// Version tag for the rewind proof-message layout below, bumped if the
+/// encoding ever changes so old outputs can still be told apart from new
+/// ones.
+const PROOF_MSG_VERSION: u8 = 0;
+/// Size, in bytes, of the fixed proof message a range proof carries.
+const PROOF_MSG_SIZE: usize = 20;
+
+/// Packs an output's derivation path into the fixed-size range proof
+/// message, together with a version tag and the identifier's own length
+/// (so the zero-padding used to fill out the fixed-size message isn't
+/// mistaken for part of the identifier on the way back out), so a wallet
+/// that only has its keychain (no other state) can later rescan the chain
+/// and recover which outputs it owns.
+fn rewind_message(pubkey: &Identifier) -> secp::pedersen::ProofMessage {
+	let mut bytes = [0u8; PROOF_MSG_SIZE];
+	bytes[0] = PROOF_MSG_VERSION;
+	let id_bytes = pubkey.to_bytes();
+	let len = id_bytes.len().min(bytes.len() - 2);
+	bytes[1] = len as u8;
+	bytes[2..2 + len].copy_from_slice(&id_bytes[..len]);
+	secp::pedersen::ProofMessage::from_bytes(&bytes)
+}
+
+/// Recovers the value and derivation path of an output this keychain owns,
+/// purely from its commitment and range proof found on chain — no other
+/// wallet state required. Unwinds the bulletproof with the same
+/// keychain-derived nonce `output` embedded it with, decodes the
+/// derivation path from the recovered message, and validates that
+/// re-committing to the recovered value and path reproduces `commit`
+/// before accepting it. Returns `None` if the output isn't ours, or the
+/// proof doesn't validate.
+pub fn rewind_output(
+	commit: secp::pedersen::Commitment,
+	proof: secp::pedersen::RangeProof,
+	keychain: &keychain::Keychain,
+) -> Option<(u64, Identifier)> {
+	let nonce = keychain.rewind_nonce(&commit).ok()?;
+	let info = keychain.rewind_range_proof(commit, proof, nonce).ok()?;
+	if !info.success || info.message.len() < 2 || info.message[0] != PROOF_MSG_VERSION {
+		return None;
+	}
+
+	let len = info.message[1] as usize;
+	if info.message.len() < 2 + len {
+		return None;
+	}
+	let pubkey = Identifier::from_bytes(&info.message[2..2 + len]);
+	if keychain.commit(info.value, &pubkey).ok()? != commit {
+		return None;
+	}
+	Some((info.value, pubkey))
 }
 
 // Just a simple test, most exhaustive tests in the core mod.rs.
@@ -153,4 +540,267 @@ mod test {
 
 		tx.verify_sig(&keychain.secp()).unwrap();
 	}
+
+	#[test]
+	fn offset_accumulates_under_chaining() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let pk1 = keychain.derive_pubkey(1).unwrap();
+		let pk2 = keychain.derive_pubkey(2).unwrap();
+		let pk3 = keychain.derive_pubkey(3).unwrap();
+
+		let (tx1, sum1) = transaction(
+			vec![input(10, pk1), output(2, pk2), with_fee(2)],
+			&keychain,
+		).unwrap();
+		let offset1 = tx1.offset.clone();
+
+		let extra_offset = random_offset(keychain.secp());
+		let (tx2, _) = transaction(
+			vec![
+				initial_tx(tx1),
+				with_excess(sum1),
+				with_offset(extra_offset.clone()),
+				output(6, pk3),
+				with_fee(2),
+			],
+			&keychain,
+		).unwrap();
+
+		let expected = offset1.add(keychain.secp(), &extra_offset).unwrap();
+		assert_eq!(tx2.offset, expected);
+		tx2.verify_sig(&keychain.secp()).unwrap();
+	}
+
+	#[test]
+	fn two_party_partial_sign_round_trip() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let pk1 = keychain.derive_pubkey(1).unwrap();
+		let pk2 = keychain.derive_pubkey(2).unwrap();
+
+		let (party_a, blind_a, nonce_a) = partial_transaction(
+			vec![input(10, pk1)], 1, 0, &keychain,
+		).unwrap();
+		let (party_b, blind_b, nonce_b) = partial_transaction(
+			vec![output(9, pk2)], 1, 0, &keychain,
+		).unwrap();
+		let parts = vec![party_a, party_b];
+
+		let sig_a = sign_partial(&blind_a, &nonce_a, &parts, &keychain).unwrap();
+		let sig_b = sign_partial(&blind_b, &nonce_b, &parts, &keychain).unwrap();
+
+		verify_partial_sig(&sig_a, &parts[0], &parts, &keychain).unwrap();
+		verify_partial_sig(&sig_b, &parts[1], &parts, &keychain).unwrap();
+
+		let tx = finalize_transaction(&parts, &[sig_a, sig_b], &keychain).unwrap();
+		tx.verify_sig(&keychain.secp()).unwrap();
+	}
+
+	#[test]
+	fn finalized_tx_authenticates_lock_height() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let pk1 = keychain.derive_pubkey(1).unwrap();
+		let pk2 = keychain.derive_pubkey(2).unwrap();
+
+		let (party_a, blind_a, nonce_a) = partial_transaction(
+			vec![input(10, pk1)], 1, 100, &keychain,
+		).unwrap();
+		let (party_b, blind_b, nonce_b) = partial_transaction(
+			vec![output(9, pk2)], 1, 100, &keychain,
+		).unwrap();
+		let parts = vec![party_a, party_b];
+
+		let sig_a = sign_partial(&blind_a, &nonce_a, &parts, &keychain).unwrap();
+		let sig_b = sign_partial(&blind_b, &nonce_b, &parts, &keychain).unwrap();
+
+		let mut tx = finalize_transaction(&parts, &[sig_a, sig_b], &keychain).unwrap();
+		tx.verify_sig(&keychain.secp()).unwrap();
+
+		// A lock_height tampered with after the fact no longer matches the
+		// digest the signature was produced over, so verification must fail.
+		tx.lock_height = 200;
+		assert!(tx.verify_sig(&keychain.secp()).is_err());
+	}
+
+	#[test]
+	fn tampered_partial_sig_is_rejected() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let pk1 = keychain.derive_pubkey(1).unwrap();
+		let pk2 = keychain.derive_pubkey(2).unwrap();
+
+		let (party_a, blind_a, nonce_a) = partial_transaction(
+			vec![input(10, pk1)], 1, 0, &keychain,
+		).unwrap();
+		let (party_b, _, _) = partial_transaction(
+			vec![output(9, pk2)], 1, 0, &keychain,
+		).unwrap();
+		let parts = vec![party_a, party_b];
+
+		let mut sig_a = sign_partial(&blind_a, &nonce_a, &parts, &keychain).unwrap();
+		let bogus_nonce = nonce_secret_key(keychain.secp());
+		sig_a.add_assign(keychain.secp(), &bogus_nonce).unwrap();
+
+		assert!(verify_partial_sig(&sig_a, &parts[0], &parts, &keychain).is_err());
+	}
+
+	#[test]
+	fn finalize_rejects_no_parties() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let parts: Vec<PartialTx> = vec![];
+		let sigs: Vec<SecretKey> = vec![];
+		assert!(finalize_transaction(&parts, &sigs, &keychain).is_err());
+	}
+
+	#[test]
+	fn finalize_rejects_mismatched_fee() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let pk1 = keychain.derive_pubkey(1).unwrap();
+		let pk2 = keychain.derive_pubkey(2).unwrap();
+
+		let (party_a, _, _) = partial_transaction(
+			vec![input(10, pk1)], 1, 0, &keychain,
+		).unwrap();
+		let (party_b, _, _) = partial_transaction(
+			vec![output(9, pk2)], 2, 0, &keychain,
+		).unwrap();
+		let parts = vec![party_a, party_b];
+		let sigs = vec![
+			nonce_secret_key(keychain.secp()),
+			nonce_secret_key(keychain.secp()),
+		];
+
+		assert!(finalize_transaction(&parts, &sigs, &keychain).is_err());
+	}
+
+	#[test]
+	fn select_and_build_spends_largest_first_and_pays_fee() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let coins = vec![
+			OutputData { value: 100, key_id: keychain.derive_pubkey(1).unwrap(), is_change: false },
+			OutputData { value: 10, key_id: keychain.derive_pubkey(2).unwrap(), is_change: false },
+		];
+
+		let elems = select_and_build(&coins, 50, 1, 3, false, &keychain).unwrap();
+		let recipient = keychain.derive_pubkey(4).unwrap();
+		let mut with_recipient = elems;
+		with_recipient.push(output(50, recipient));
+
+		let (tx, _) = transaction(with_recipient, &keychain).unwrap();
+		tx.verify_sig(&keychain.secp()).unwrap();
+		assert_eq!(tx.inputs.len(), 1);
+		assert_eq!(tx.outputs.len(), 2);
+	}
+
+	#[test]
+	fn select_and_build_fails_on_insufficient_funds() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let coins = vec![
+			OutputData { value: 10, key_id: keychain.derive_pubkey(1).unwrap(), is_change: false },
+		];
+
+		match select_and_build(&coins, 50, 1, 2, false, &keychain) {
+			Err(keychain::Error::NotEnoughFunds(_)) => {}
+			other => panic!("expected NotEnoughFunds, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn select_and_build_honors_do_not_spend_change() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let coins = vec![
+			OutputData { value: 10, key_id: keychain.derive_pubkey(1).unwrap(), is_change: true },
+			OutputData { value: 5, key_id: keychain.derive_pubkey(2).unwrap(), is_change: false },
+		];
+
+		match select_and_build(&coins, 8, 1, 3, true, &keychain) {
+			Err(keychain::Error::NotEnoughFunds(total)) => assert_eq!(total, 5),
+			other => panic!("expected NotEnoughFunds over the non-change coin only, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn select_and_build_drops_zero_value_change() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let fee_rate = 1;
+		let fee = tx_fee(1, 2, fee_rate);
+		let coins = vec![
+			OutputData { value: 50 + fee, key_id: keychain.derive_pubkey(1).unwrap(), is_change: false },
+		];
+
+		let elems = select_and_build(&coins, 50, fee_rate, 2, false, &keychain).unwrap();
+		let recipient = keychain.derive_pubkey(3).unwrap();
+		let mut with_recipient = elems;
+		with_recipient.push(output(50, recipient));
+
+		let (tx, _) = transaction(with_recipient, &keychain).unwrap();
+		tx.verify_sig(&keychain.secp()).unwrap();
+		assert_eq!(tx.outputs.len(), 1);
+	}
+
+	#[test]
+	fn rewind_output_recovers_value_and_identifier() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let pubkey = keychain.derive_pubkey(7).unwrap();
+
+		let (tx, _) = transaction(
+			vec![input(20, keychain.derive_pubkey(1).unwrap()), output(18, pubkey.clone()), with_fee(2)],
+			&keychain,
+		).unwrap();
+		let out = &tx.outputs[0];
+
+		let (value, recovered) = rewind_output(out.commit, out.proof.clone(), &keychain).unwrap();
+		assert_eq!(value, 18);
+		assert_eq!(recovered, pubkey);
+	}
+
+	#[test]
+	fn rewind_output_returns_none_for_someone_elses_output() {
+		let mine = Keychain::from_random_seed().unwrap();
+		let theirs = Keychain::from_random_seed().unwrap();
+		let pubkey = theirs.derive_pubkey(1).unwrap();
+
+		let (tx, _) = transaction(
+			vec![input(20, theirs.derive_pubkey(2).unwrap()), output(18, pubkey), with_fee(2)],
+			&theirs,
+		).unwrap();
+		let out = &tx.outputs[0];
+
+		assert!(rewind_output(out.commit, out.proof.clone(), &mine).is_none());
+	}
+
+	#[test]
+	fn scripted_output_signature_is_valid() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let pk1 = keychain.derive_pubkey(1).unwrap();
+		let pk2 = keychain.derive_pubkey(2).unwrap();
+		let script = Script::new(vec![0x51]);
+
+		let (tx, _) = transaction(
+			vec![input(10, pk1), output_with_script(9, pk2, script), with_fee(1)],
+			&keychain,
+		).unwrap();
+
+		assert!(tx.outputs[0].script.is_some());
+		tx.verify_sig(&keychain.secp()).unwrap();
+	}
+
+	#[test]
+	fn multiple_scripted_outputs_accumulate_script_offset() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let pk1 = keychain.derive_pubkey(1).unwrap();
+		let pk2 = keychain.derive_pubkey(2).unwrap();
+		let pk3 = keychain.derive_pubkey(3).unwrap();
+
+		let (tx, _) = transaction(
+			vec![
+				input(20, pk1),
+				output_with_script(9, pk2, Script::new(vec![0x51])),
+				output_with_script(9, pk3, Script::new(vec![0x52])),
+				with_fee(2),
+			],
+			&keychain,
+		).unwrap();
+
+		assert_eq!(tx.outputs.len(), 2);
+		tx.verify_sig(&keychain.secp()).unwrap();
+	}
 }
@@ -0,0 +1,213 @@
+// Copyright 2016 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transaction and output types, plus the combinators in `build` used to
+//! assemble them.
+
+use blake2_rfc::blake2b::blake2b;
+use byteorder::{BigEndian, ByteOrder};
+use secp;
+
+use keychain;
+use keychain::BlindingFactor;
+
+pub mod build;
+
+/// The canonical 32-byte digest of a kernel's fee and lock height. Used as
+/// the message a kernel's excess signature is produced/verified over, so
+/// `lock_height` is authenticated by the signature the same way `fee` is,
+/// instead of being carried on the transaction unchecked.
+pub(crate) fn signing_digest(fee: u64, lock_height: u64) -> [u8; 32] {
+	let mut bytes = [0u8; 16];
+	BigEndian::write_u64(&mut bytes[0..8], fee);
+	BigEndian::write_u64(&mut bytes[8..16], lock_height);
+	let hash = blake2b(32, &[], &bytes);
+	let mut digest = [0u8; 32];
+	digest.copy_from_slice(hash.as_bytes());
+	digest
+}
+
+/// Flags carried by an output. Only the default exists today.
+pub type OutputFeatures = u8;
+
+/// The only feature flag outputs carry today.
+pub const DEFAULT_OUTPUT: OutputFeatures = 0;
+
+/// A spending condition attached to an output. Opaque to `core` itself —
+/// it's carried along with the output and checked by whatever script
+/// engine the consensus rules plug in, but every scripted output folds a
+/// contribution into the transaction's script offset, so a kernel
+/// signature is only valid for the exact set of scripts it was built
+/// against.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Script(Vec<u8>);
+
+impl Script {
+	/// Wraps the raw script bytes.
+	pub fn new(bytes: Vec<u8>) -> Script {
+		Script(bytes)
+	}
+
+	/// The raw script bytes.
+	pub fn bytes(&self) -> &[u8] {
+		&self.0
+	}
+}
+
+/// A transaction input: a reference to the output being spent.
+#[derive(Clone, Debug)]
+pub struct Input(pub secp::pedersen::Commitment);
+
+/// A transaction output: a commitment to a value, its range proof, and an
+/// optional spending condition.
+#[derive(Clone, Debug)]
+pub struct Output {
+	pub features: OutputFeatures,
+	pub commit: secp::pedersen::Commitment,
+	pub proof: secp::pedersen::RangeProof,
+	pub script: Option<Script>,
+}
+
+/// Errors that can occur while assembling or validating a transaction.
+#[derive(Debug)]
+pub enum Error {
+	Secp(secp::Error),
+	Keychain(keychain::Error),
+}
+
+impl From<secp::Error> for Error {
+	fn from(e: secp::Error) -> Error {
+		Error::Secp(e)
+	}
+}
+
+impl From<keychain::Error> for Error {
+	fn from(e: keychain::Error) -> Error {
+		Error::Keychain(e)
+	}
+}
+
+/// A Grin transaction, as assembled by `build::transaction`.
+#[derive(Clone, Debug)]
+pub struct Transaction {
+	pub inputs: Vec<Input>,
+	pub outputs: Vec<Output>,
+	pub fee: u64,
+	pub lock_height: u64,
+	/// Kernel offset `o`, subtracted from the blinding sum before signing
+	/// so aggregated/cut-through kernels don't leak their individual
+	/// excess values. Serializes to 32 bytes.
+	pub offset: BlindingFactor,
+	/// Aggregate script offset `γ`, folded into the excess the same way
+	/// the kernel offset is, so the kernel signature is only valid for
+	/// the exact set of scripted outputs used to build it. Inputs carry no
+	/// script and add nothing to it.
+	pub script_offset: BlindingFactor,
+	pub excess_sig: Vec<u8>,
+}
+
+impl Transaction {
+	/// An empty transaction: no inputs, outputs, fee or offset.
+	pub fn empty() -> Transaction {
+		Transaction {
+			inputs: vec![],
+			outputs: vec![],
+			fee: 0,
+			lock_height: 0,
+			offset: BlindingFactor::zero(),
+			script_offset: BlindingFactor::zero(),
+			excess_sig: vec![],
+		}
+	}
+
+	pub fn with_input(mut self, input: Input) -> Transaction {
+		self.inputs.push(input);
+		self
+	}
+
+	pub fn with_output(mut self, output: Output) -> Transaction {
+		self.outputs.push(output);
+		self
+	}
+
+	pub fn with_fee(mut self, fee: u64) -> Transaction {
+		self.fee = fee;
+		self
+	}
+
+	pub fn with_lock_height(mut self, lock_height: u64) -> Transaction {
+		self.lock_height = lock_height;
+		self
+	}
+
+	/// Sets the kernel offset. Callers that want to accumulate rather than
+	/// replace should pass in the already-summed total, e.g.
+	/// `self.offset.add(secp, &extra)`.
+	pub fn with_offset(mut self, offset: BlindingFactor) -> Transaction {
+		self.offset = offset;
+		self
+	}
+
+	/// Sets the script offset, with the same accumulation convention as
+	/// `with_offset`.
+	pub fn with_script_offset(mut self, script_offset: BlindingFactor) -> Transaction {
+		self.script_offset = script_offset;
+		self
+	}
+
+	/// Merges another party's inputs, outputs and offsets into this
+	/// transaction. Used to assemble the final transaction out of every
+	/// party's contribution to an interactive transaction.
+	pub fn merge(mut self, other: Transaction, secp: &secp::Secp256k1) -> Transaction {
+		self.inputs.extend(other.inputs);
+		self.outputs.extend(other.outputs);
+		self.offset = self.offset.add(secp, &other.offset).expect("offset merge");
+		self.script_offset = self.script_offset
+			.add(secp, &other.script_offset)
+			.expect("script offset merge");
+		self
+	}
+
+	/// Checks that the kernel excess signature is valid, and that the
+	/// kernel excess — once adjusted by the kernel offset and the script
+	/// offset — balances against the Pedersen sum of outputs, inputs and
+	/// fee:
+	///
+	/// `Σkernel_excess + o·G + γ·G == Σoutputs - Σinputs - fee·H`
+	///
+	/// The signature is checked against `signing_digest(fee, lock_height)`,
+	/// so this also authenticates `lock_height` — the same digest a
+	/// multi-party transaction's `aggregate_challenge` folds into its
+	/// Schnorr challenge, so a finalized interactive transaction verifies
+	/// under the exact message it was actually signed over.
+	pub fn verify_sig(&self, secp: &secp::Secp256k1) -> Result<(), Error> {
+		let out_commits: Vec<_> = self.outputs.iter().map(|o| o.commit).collect();
+		let in_commits: Vec<_> = self.inputs.iter().map(|i| i.0).collect();
+
+		let io_sum = secp.commit_sum(out_commits, in_commits)?;
+		let fee_commit = secp.commit_value(self.fee)?;
+		let balance = secp.commit_sum(vec![io_sum], vec![fee_commit])?;
+
+		let offset_commit = secp.commit(0, self.offset.secret_key(secp)?)?;
+		let script_offset_commit = secp.commit(0, self.script_offset.secret_key(secp)?)?;
+		let excess = secp.commit_sum(vec![balance], vec![offset_commit, script_offset_commit])?;
+
+		let pubkey = excess.to_pubkey(secp)?;
+		let sig = secp::Signature::from_der(secp, &self.excess_sig)?;
+		let msg = secp::Message::from_slice(&signing_digest(self.fee, self.lock_height))?;
+
+		secp.verify(&msg, &sig, &pubkey)?;
+		Ok(())
+	}
+}
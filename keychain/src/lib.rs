@@ -0,0 +1,273 @@
+// Copyright 2016 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Derives a wallet's blinding keys, rewind nonces and script scaling
+//! factors from a single master seed, and accumulates the sums `core`'s
+//! `build` module needs to produce a transaction's kernel excess.
+
+extern crate blake2_rfc;
+extern crate byteorder;
+extern crate rand;
+extern crate secp;
+
+use blake2_rfc::blake2b::blake2b;
+use byteorder::{BigEndian, ByteOrder};
+use rand::{OsRng, Rng};
+use secp::key::SecretKey;
+
+/// Errors produced while deriving keys, building commitments or combining
+/// blinding factors.
+#[derive(Debug)]
+pub enum Error {
+	Secp(secp::Error),
+	NotEnoughFunds(u64),
+	/// An interactive-transaction operation was given no parties to work
+	/// with.
+	NoParties,
+	/// The parties to an interactive transaction disagree on the fee or
+	/// lock height.
+	PartyMismatch,
+}
+
+impl From<secp::Error> for Error {
+	fn from(e: secp::Error) -> Error {
+		Error::Secp(e)
+	}
+}
+
+/// A derivation path identifying one of a keychain's outputs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identifier(Vec<u8>);
+
+impl Identifier {
+	pub fn from_index(index: u32) -> Identifier {
+		let mut bytes = [0u8; 4];
+		BigEndian::write_u32(&mut bytes, index);
+		Identifier(bytes.to_vec())
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		self.0.clone()
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Identifier {
+		Identifier(bytes.to_vec())
+	}
+}
+
+/// A blinding factor (a secp256k1 scalar), stored as 32 bytes so it
+/// serializes directly onto a `Transaction`'s `offset`/`script_offset`
+/// fields.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlindingFactor([u8; 32]);
+
+impl BlindingFactor {
+	/// The neutral element: no blinding, no offset.
+	pub fn zero() -> BlindingFactor {
+		BlindingFactor([0; 32])
+	}
+
+	pub fn from_secret_key(key: SecretKey) -> BlindingFactor {
+		let mut bytes = [0u8; 32];
+		bytes.copy_from_slice(&key[..]);
+		BlindingFactor(bytes)
+	}
+
+	pub fn secret_key(&self, secp: &secp::Secp256k1) -> Result<SecretKey, Error> {
+		SecretKey::from_slice(secp, &self.0).map_err(Error::Secp)
+	}
+
+	/// Adds two blinding factors together as secp256k1 scalars, treating
+	/// `zero()` as the identity so a never-set offset doesn't need a
+	/// special case at every call site.
+	pub fn add(&self, secp: &secp::Secp256k1, other: &BlindingFactor) -> Result<BlindingFactor, Error> {
+		if *self == BlindingFactor::zero() {
+			return Ok(other.clone());
+		}
+		if *other == BlindingFactor::zero() {
+			return Ok(self.clone());
+		}
+		let mut key = self.secret_key(secp)?;
+		key.add_assign(secp, &other.secret_key(secp)?)?;
+		Ok(BlindingFactor::from_secret_key(key))
+	}
+}
+
+/// Accumulates the positive and negative blinding contributions `build`'s
+/// combinators fold a transaction through, resolved into a single
+/// `BlindingFactor` by `Keychain::blind_sum`.
+pub struct BlindSum {
+	positive_key_ids: Vec<Identifier>,
+	negative_key_ids: Vec<Identifier>,
+	positive_factors: Vec<BlindingFactor>,
+	negative_offsets: Vec<BlindingFactor>,
+	negative_script_offsets: Vec<SecretKey>,
+}
+
+impl BlindSum {
+	pub fn new() -> BlindSum {
+		BlindSum {
+			positive_key_ids: vec![],
+			negative_key_ids: vec![],
+			positive_factors: vec![],
+			negative_offsets: vec![],
+			negative_script_offsets: vec![],
+		}
+	}
+
+	pub fn add_pubkey(mut self, key_id: Identifier) -> BlindSum {
+		self.positive_key_ids.push(key_id);
+		self
+	}
+
+	pub fn sub_pubkey(mut self, key_id: Identifier) -> BlindSum {
+		self.negative_key_ids.push(key_id);
+		self
+	}
+
+	pub fn add_blinding_factor(mut self, factor: BlindingFactor) -> BlindSum {
+		self.positive_factors.push(factor);
+		self
+	}
+
+	/// Subtracts a kernel offset share from the sum, so the resulting
+	/// blinding factor nets out to `blind_sum - offset`.
+	pub fn sub_offset(mut self, offset: BlindingFactor) -> BlindSum {
+		self.negative_offsets.push(offset);
+		self
+	}
+
+	/// Subtracts a script offset contribution `hash(script)·key` from the
+	/// sum, the same way `sub_offset` handles the kernel offset.
+	pub fn sub_script_offset(mut self, gamma: SecretKey) -> BlindSum {
+		self.negative_script_offsets.push(gamma);
+		self
+	}
+}
+
+/// A wallet's keychain: every blinding key, rewind nonce and script
+/// scaling factor is deterministically derived from a single master seed,
+/// so nothing but the seed itself needs to be backed up.
+pub struct Keychain {
+	secp: secp::Secp256k1,
+	seed: Vec<u8>,
+}
+
+impl Keychain {
+	pub fn from_random_seed() -> Result<Keychain, Error> {
+		let mut rng = OsRng::new().expect("failed to get a secure RNG for the keychain seed");
+		let mut seed = vec![0u8; 32];
+		rng.fill_bytes(&mut seed);
+		Ok(Keychain { secp: secp::Secp256k1::new(), seed: seed })
+	}
+
+	pub fn secp(&self) -> &secp::Secp256k1 {
+		&self.secp
+	}
+
+	pub fn derive_pubkey(&self, index: u32) -> Result<Identifier, Error> {
+		Ok(Identifier::from_index(index))
+	}
+
+	fn derive_secret_key(&self, id: &Identifier) -> Result<SecretKey, Error> {
+		let mut input = self.seed.clone();
+		input.extend_from_slice(&id.to_bytes());
+		let hash = blake2b(32, &[], &input);
+		SecretKey::from_slice(&self.secp, hash.as_bytes()).map_err(Error::Secp)
+	}
+
+	pub fn commit(&self, value: u64, id: &Identifier) -> Result<secp::pedersen::Commitment, Error> {
+		let key = self.derive_secret_key(id)?;
+		self.secp.commit(value, key).map_err(Error::Secp)
+	}
+
+	pub fn range_proof(
+		&self,
+		value: u64,
+		id: &Identifier,
+		commit: secp::pedersen::Commitment,
+		msg: secp::pedersen::ProofMessage,
+		nonce: SecretKey,
+	) -> Result<secp::pedersen::RangeProof, Error> {
+		let key = self.derive_secret_key(id)?;
+		Ok(self.secp.bullet_proof(value, key, nonce, commit, msg))
+	}
+
+	/// The nonce used to rewind a range proof, bound to `commit` so every
+	/// output gets a distinct nonce instead of the whole wallet reusing one.
+	/// The commitment is available both when the output is first built and
+	/// later when rewinding it off-chain, so it doubles as a derivation
+	/// path without creating a chicken-and-egg dependency on the identifier
+	/// `rewind_output` is trying to recover.
+	pub fn rewind_nonce(&self, commit: &secp::pedersen::Commitment) -> Result<SecretKey, Error> {
+		let mut input = self.seed.clone();
+		input.extend_from_slice(b"rewind");
+		input.extend_from_slice(&commit.0[..]);
+		let hash = blake2b(32, &[], &input);
+		SecretKey::from_slice(&self.secp, hash.as_bytes()).map_err(Error::Secp)
+	}
+
+	pub fn rewind_range_proof(
+		&self,
+		commit: secp::pedersen::Commitment,
+		proof: secp::pedersen::RangeProof,
+		nonce: SecretKey,
+	) -> Result<secp::pedersen::ProofInfo, Error> {
+		self.secp.rewind_bullet_proof(commit, nonce, proof).map_err(Error::Secp)
+	}
+
+	/// Scales `factor` by the secret key behind `id`, without ever handing
+	/// the derived key itself back to the caller. Used to fold a derived
+	/// key into a script-offset contribution (or similar per-output scalar)
+	/// while keeping every raw blinding key inside the keychain.
+	pub fn scale_by_key(&self, id: &Identifier, factor: &SecretKey) -> Result<SecretKey, Error> {
+		let key = self.derive_secret_key(id)?;
+		let mut scaled = factor.clone();
+		scaled.mul_assign(&self.secp, &key)?;
+		Ok(scaled)
+	}
+
+	pub fn sign_with_blinding(
+		&self,
+		msg: &secp::Message,
+		blinding: &BlindingFactor,
+	) -> Result<secp::Signature, Error> {
+		let key = blinding.secret_key(&self.secp)?;
+		self.secp.sign(msg, &key).map_err(Error::Secp)
+	}
+
+	pub fn blind_sum(&self, sum: &BlindSum) -> Result<BlindingFactor, Error> {
+		let mut positive: Vec<SecretKey> = Vec::new();
+		for id in &sum.positive_key_ids {
+			positive.push(self.derive_secret_key(id)?);
+		}
+		for factor in &sum.positive_factors {
+			positive.push(factor.secret_key(&self.secp)?);
+		}
+
+		let mut negative: Vec<SecretKey> = Vec::new();
+		for id in &sum.negative_key_ids {
+			negative.push(self.derive_secret_key(id)?);
+		}
+		for offset in &sum.negative_offsets {
+			negative.push(offset.secret_key(&self.secp)?);
+		}
+		for gamma in &sum.negative_script_offsets {
+			negative.push(gamma.clone());
+		}
+
+		let total = self.secp.blind_sum(positive, negative)?;
+		Ok(BlindingFactor::from_secret_key(total))
+	}
+}